@@ -0,0 +1,380 @@
+//! An async variant of the streaming `Archive`/`Builder` API, built on
+//! `tokio::io::{AsyncRead, AsyncWrite}`, so that archives can be read and
+//! written by async servers and tools without blocking a thread.
+//!
+//! This mirrors the approach taken by the `tokio-tar` crate: the header
+//! parsing/formatting logic is shared with the synchronous implementation
+//! (see `RawEntryHeader` and `build_entry_header_bytes`), and only the I/O
+//! layer differs. As with the synchronous `Archive`, no full member is ever
+//! buffered into memory.
+
+use crate::read::{parse_entry_header, resolve_gnu_identifier, RawEntryHeader};
+use crate::write::build_entry_header_bytes;
+use crate::{
+    invalid_data, Header, Variant, ENTRY_HEADER_LEN, GLOBAL_HEADER, GLOBAL_HEADER_LEN,
+    GNU_NAME_TABLE_ID, GNU_SYMBOL_LOOKUP_TABLE_ID,
+};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// ========================================================================= //
+
+/// An async structure for reading an archive, decoding it as it goes.
+///
+/// Like the synchronous `Archive`, this reads members sequentially and
+/// never buffers more than one member's data into memory at a time.
+/// Currently understands the common and GNU variants; BSD extended names
+/// (`#1/N`) are left to the synchronous `Archive`.
+pub struct AsyncArchive<R> {
+    reader: R,
+    scanned_header: bool,
+    variant: Variant,
+    name_table: Vec<u8>,
+    current_entry_remaining: u64,
+    current_entry_padding: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    /// Creates a new archive reader from the underlying reader object.
+    pub fn new(reader: R) -> AsyncArchive<R> {
+        AsyncArchive {
+            reader,
+            scanned_header: false,
+            variant: Variant::Common,
+            name_table: Vec::new(),
+            current_entry_remaining: 0,
+            current_entry_padding: 0,
+        }
+    }
+
+    /// Returns the format variant that this archive appears to be encoded
+    /// in. This starts out as a guess (`Common`), and may change as more of
+    /// the archive is read.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Unwraps this archive reader, returning the underlying reader object.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    async fn skip_to_next_header(&mut self) -> io::Result<()> {
+        let mut remaining = self.current_entry_remaining + self.current_entry_padding;
+        let mut buffer = [0u8; 4096];
+        while remaining > 0 {
+            let want = std::cmp::min(remaining, buffer.len() as u64) as usize;
+            let read = self.reader.read(&mut buffer[..want]).await?;
+            if read == 0 {
+                return Err(invalid_data("Archive is truncated"));
+            }
+            remaining -= read as u64;
+        }
+        self.current_entry_remaining = 0;
+        self.current_entry_padding = 0;
+        Ok(())
+    }
+
+    /// Reads the next entry from the archive, or returns `None` if there
+    /// are no more entries to be read.
+    pub async fn next_entry(&mut self) -> Option<io::Result<AsyncEntry<'_, R>>> {
+        loop {
+            if !self.scanned_header {
+                let mut magic = [0u8; GLOBAL_HEADER_LEN];
+                match self.reader.read_exact(&mut magic).await {
+                    Ok(_) => {}
+                    Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+                    Err(err) => return Some(Err(err)),
+                }
+                if &magic != GLOBAL_HEADER {
+                    return Some(Err(invalid_data("Invalid global header")));
+                }
+                self.scanned_header = true;
+            } else if let Err(err) = self.skip_to_next_header().await {
+                return Some(Err(err));
+            }
+
+            let mut raw_header = [0u8; ENTRY_HEADER_LEN];
+            match self.reader.read_exact(&mut raw_header).await {
+                Ok(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+                Err(err) => return Some(Err(err)),
+            }
+            let RawEntryHeader { mut identifier, mtime, uid, gid, mode, size } =
+                match parse_entry_header(&raw_header) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Some(Err(err)),
+                };
+
+            if identifier.as_slice() == GNU_NAME_TABLE_ID.as_bytes() {
+                let mut table = vec![0u8; size as usize];
+                if let Err(err) = self.reader.read_exact(&mut table).await {
+                    return Some(Err(err));
+                }
+                if !size.is_multiple_of(2) {
+                    let mut pad = [0u8; 1];
+                    if let Err(err) = self.reader.read_exact(&mut pad).await {
+                        return Some(Err(err));
+                    }
+                }
+                self.name_table = table;
+                self.variant = Variant::GNU;
+                continue;
+            }
+
+            if identifier.as_slice() == GNU_SYMBOL_LOOKUP_TABLE_ID.as_bytes() {
+                let mut table = vec![0u8; size as usize];
+                if let Err(err) = self.reader.read_exact(&mut table).await {
+                    return Some(Err(err));
+                }
+                if !size.is_multiple_of(2) {
+                    let mut pad = [0u8; 1];
+                    if let Err(err) = self.reader.read_exact(&mut pad).await {
+                        return Some(Err(err));
+                    }
+                }
+                self.variant = Variant::GNU;
+                continue;
+            }
+
+            if identifier.starts_with(b"/") || identifier.ends_with(b"/") {
+                identifier = match resolve_gnu_identifier(identifier, &self.name_table) {
+                    Ok(identifier) => identifier,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.variant = Variant::GNU;
+            }
+
+            let header = Header { identifier, mtime, uid, gid, mode, size };
+            self.current_entry_remaining = size;
+            self.current_entry_padding = size % 2;
+            return Some(Ok(AsyncEntry {
+                header,
+                reader: &mut self.reader,
+                remaining: &mut self.current_entry_remaining,
+            }));
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// An entry being read from an async archive.
+///
+/// This implements `tokio::io::AsyncRead` for the raw contents of the
+/// member, and will never read more than `header().size()` bytes.
+pub struct AsyncEntry<'a, R: 'a> {
+    header: Header,
+    reader: &'a mut R,
+    remaining: &'a mut u64,
+}
+
+impl<'a, R: 'a> AsyncEntry<'a, R> {
+    /// Returns the header for this archive entry.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin + 'a> AsyncRead for AsyncEntry<'a, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if *this.remaining == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let limit = std::cmp::min(*this.remaining, buf.remaining() as u64) as usize;
+        let mut limited = buf.take(limit);
+        let ptr = limited.filled().as_ptr();
+        match Pin::new(&mut *this.reader).poll_read(cx, &mut limited) {
+            Poll::Ready(Ok(())) => {
+                assert_eq!(limited.filled().as_ptr(), ptr);
+                let read = limited.filled().len();
+                unsafe {
+                    buf.assume_init(read);
+                }
+                buf.advance(read);
+                *this.remaining -= read as u64;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// An async structure for building common-variant archives, written out in
+/// one go to a writer.
+///
+/// This writes a global archive header up front, then writes each member in
+/// turn as `append`/`append_file`/`append_path` are called, without ever
+/// buffering a whole member's data into memory.
+pub struct AsyncBuilder<W: AsyncWrite + Unpin> {
+    writer: W,
+    started: bool,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBuilder<W> {
+    /// Creates a new archive builder with the underlying writer object, to
+    /// be written in the standard "common" format.
+    pub fn new(writer: W) -> AsyncBuilder<W> {
+        AsyncBuilder { writer, started: false }
+    }
+
+    async fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.writer.write_all(GLOBAL_HEADER).await?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive with the given header and contents.
+    /// The header's identifier must be no more than 16 bytes, and must not
+    /// contain any whitespace.
+    pub async fn append<R: AsyncRead + Unpin>(
+        &mut self,
+        header: &Header,
+        data: &mut R,
+    ) -> io::Result<()> {
+        self.start().await?;
+        let identifier = header.identifier();
+        if identifier.len() > 16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Identifier in header is too long for this archive variant",
+            ));
+        }
+        if identifier.iter().any(u8::is_ascii_whitespace) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Identifier in header contains whitespace",
+            ));
+        }
+        let raw = build_entry_header_bytes(identifier, header, header.size());
+        self.writer.write_all(&raw).await?;
+
+        let mut remaining = header.size();
+        let mut buffer = [0u8; 8192];
+        while remaining > 0 {
+            let want = std::cmp::min(remaining, buffer.len() as u64) as usize;
+            let read = data.read(&mut buffer[..want]).await?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Wrote fewer bytes than the header's declared size",
+                ));
+            }
+            self.writer.write_all(&buffer[..read]).await?;
+            remaining -= read as u64;
+        }
+        if !header.size().is_multiple_of(2) {
+            self.writer.write_all(b"\n").await?;
+        }
+        Ok(())
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the given
+    /// name as the identifier for the archive entry.
+    pub async fn append_file(&mut self, name: &[u8], file: &mut File) -> io::Result<()> {
+        let metadata = file.metadata().await?;
+        let header = Header::from_metadata(name.to_vec(), &metadata);
+        self.append(&header, file).await
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the
+    /// file's base name as the identifier for the archive entry.
+    pub async fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Given path doesn't have a file name")
+            })?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes();
+        let mut file = File::open(path).await?;
+        self.append_file(&name, &mut file).await
+    }
+
+    /// Unwraps this archive builder, returning the underlying writer object.
+    pub async fn into_inner(mut self) -> io::Result<W> {
+        self.start().await?;
+        Ok(self.writer)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_round_trip_small_archive() {
+        let mut buf: Vec<u8> = Vec::new();
+        {
+            let mut builder = AsyncBuilder::new(&mut buf);
+            builder.append(&Header::new(b"a.txt".to_vec(), 5), &mut &b"hello"[..]).await.unwrap();
+            builder.append(&Header::new(b"b.txt".to_vec(), 3), &mut &b"xyz"[..]).await.unwrap();
+            builder.into_inner().await.unwrap();
+        }
+
+        let mut archive = AsyncArchive::new(&buf[..]);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.header().identifier(), b"a.txt");
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents, b"hello");
+        drop(entry);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.header().identifier(), b"b.txt");
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents, b"xyz");
+        drop(entry);
+
+        assert!(archive.next_entry().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn async_entry_poll_read_honors_remaining_across_small_chunks() {
+        let content = vec![b'x'; 5000];
+        let mut buf: Vec<u8> = Vec::new();
+        {
+            let mut builder = AsyncBuilder::new(&mut buf);
+            let header = Header::new(b"big.bin".to_vec(), content.len() as u64);
+            builder.append(&header, &mut &content[..]).await.unwrap();
+            builder.into_inner().await.unwrap();
+        }
+
+        let mut archive = AsyncArchive::new(&buf[..]);
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+
+        // Read in chunks smaller than the member's size, and smaller than
+        // the underlying buffer's own reads, to exercise the `remaining`
+        // bookkeeping in `AsyncEntry::poll_read` across multiple calls.
+        let mut out = Vec::new();
+        let mut chunk = [0u8; 777];
+        loop {
+            let read = entry.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            out.extend_from_slice(&chunk[..read]);
+        }
+        assert_eq!(out, content);
+    }
+}
@@ -32,10 +32,9 @@
 //!
 //! ```no_run
 //! use ar::Builder;
-//! use std::collections::BTreeMap;
 //! use std::fs::File;
 //! // Create a new archive that will be written to foo.a:
-//! let mut builder = Builder::new(File::create("foo.a").unwrap(), BTreeMap::new()).unwrap();
+//! let mut builder = Builder::new(File::create("foo.a").unwrap());
 //! // Add foo/bar.txt to the archive, under the name "bar.txt":
 //! builder.append_path("foo/bar.txt").unwrap();
 //! // Add foo/baz.txt to the archive, under the name "hello.txt":
@@ -70,18 +69,39 @@
 mod read;
 mod write;
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 pub use read::{Archive, Entry, SymbolTableEntry, Symbols};
-pub use write::{Builder, GnuBuilder};
+pub use write::{Builder, GnuBuilder, HeaderMode};
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::{AsyncArchive, AsyncBuilder, AsyncEntry};
 
 use std::fs::Metadata;
+use std::io;
 
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
 
 // ========================================================================= //
 
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// ========================================================================= //
+
 const GLOBAL_HEADER_LEN: usize = 8;
-const GLOBAL_HEADER: &'static [u8; GLOBAL_HEADER_LEN] = b"!<arch>\n";
+const GLOBAL_HEADER: &[u8; GLOBAL_HEADER_LEN] = b"!<arch>\n";
+
+/// The global magic used by GNU "thin" archives, whose members store only
+/// headers (with the underlying file data living outside of the archive,
+/// referenced by identifier) rather than embedding each member's contents.
+const GLOBAL_HEADER_THIN: &[u8; GLOBAL_HEADER_LEN] = b"!<thin>\n";
 
 const ENTRY_HEADER_LEN: usize = 60;
 
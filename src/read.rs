@@ -0,0 +1,647 @@
+use super::{
+    invalid_data, Header, Variant, BSD_SORTED_SYMBOL_LOOKUP_TABLE_ID,
+    BSD_SYMBOL_LOOKUP_TABLE_ID, ENTRY_HEADER_LEN, GLOBAL_HEADER, GLOBAL_HEADER_LEN,
+    GLOBAL_HEADER_THIN, GNU_NAME_TABLE_ID, GNU_SYMBOL_LOOKUP_TABLE_ID,
+};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::str;
+
+// ========================================================================= //
+
+fn bytes_to_ascii_trimmed(bytes: &[u8]) -> &[u8] {
+    let mut end = bytes.len();
+    while end > 0 && (bytes[end - 1] as char).is_whitespace() {
+        end -= 1;
+    }
+    &bytes[..end]
+}
+
+fn parse_ascii_u64(bytes: &[u8], radix: u32) -> io::Result<u64> {
+    let trimmed = bytes_to_ascii_trimmed(bytes);
+    if trimmed.is_empty() {
+        return Ok(0);
+    }
+    let string =
+        str::from_utf8(trimmed).map_err(|_| invalid_data("Header field is not valid UTF-8"))?;
+    u64::from_str_radix(string, radix).map_err(|_| invalid_data("Invalid integer in header field"))
+}
+
+// ========================================================================= //
+
+/// The fields of an entry header, parsed from its raw 60-byte on-disk
+/// representation but before any format-specific interpretation of the
+/// identifier (GNU long names, BSD extended names, special table members)
+/// has been applied.
+///
+/// This parsing is pure (no I/O), so it can be shared between the
+/// synchronous reader below and the `tokio`-based `AsyncArchive`, which
+/// only differ in how they get the raw bytes off the wire.
+pub(crate) struct RawEntryHeader {
+    pub(crate) identifier: Vec<u8>,
+    pub(crate) mtime: u64,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) mode: u32,
+    pub(crate) size: u64,
+}
+
+/// Resolves a raw GNU-variant entry identifier into its final form.
+///
+/// GNU `ar` encodes a member name in the entry header in one of two ways: a
+/// short name (<= 15 bytes) is stored literally with a trailing `/` (e.g.
+/// `"foo.o/"`), while a longer name is stored as a reference into the name
+/// table written earlier as a `GNU_NAME_TABLE_ID` member, encoded as a
+/// *leading* `/` followed by a decimal offset and no trailing `/` (e.g.
+/// `"/0"`). See `gnu_identifier_for_name` in `write.rs` for the writing
+/// side of this encoding.
+///
+/// This is pure (no I/O), so it is shared between the synchronous
+/// `Archive` below and the `tokio`-based `AsyncArchive`.
+pub(crate) fn resolve_gnu_identifier(
+    mut identifier: Vec<u8>,
+    name_table: &[u8],
+) -> io::Result<Vec<u8>> {
+    if identifier.starts_with(b"/") && identifier.as_slice() != b"/" && identifier.as_slice() != b"//"
+    {
+        let offset = str::from_utf8(&identifier[1..])
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| invalid_data("Invalid GNU name table offset"))?;
+        if offset > name_table.len() {
+            return Err(invalid_data("GNU name table offset out of range"));
+        }
+        // Each name table entry is terminated by the two bytes `/\n` (see
+        // `GnuBuilder::new_impl` in `write.rs`), not a bare `/`: a thin
+        // archive's identifier may itself be a path containing `/`
+        // components, and only the `/\n` pair marks the true end of an
+        // entry.
+        let end = name_table[offset..]
+            .windows(2)
+            .position(|pair| pair == b"/\n")
+            .map(|pos| offset + pos)
+            .unwrap_or(name_table.len());
+        Ok(name_table[offset..end].to_vec())
+    } else if identifier.ends_with(b"/") && identifier.as_slice() != b"/" && identifier.as_slice() != b"//"
+    {
+        identifier.pop();
+        Ok(identifier)
+    } else {
+        Ok(identifier)
+    }
+}
+
+pub(crate) fn parse_entry_header(raw: &[u8; ENTRY_HEADER_LEN]) -> io::Result<RawEntryHeader> {
+    if &raw[58..60] != b"`\n" {
+        return Err(invalid_data("Invalid entry header"));
+    }
+    Ok(RawEntryHeader {
+        identifier: bytes_to_ascii_trimmed(&raw[0..16]).to_vec(),
+        mtime: parse_ascii_u64(&raw[16..28], 10)?,
+        uid: parse_ascii_u64(&raw[28..34], 10)? as u32,
+        gid: parse_ascii_u64(&raw[34..40], 10)? as u32,
+        mode: parse_ascii_u64(&raw[40..48], 8)? as u32,
+        size: parse_ascii_u64(&raw[48..58], 10)?,
+    })
+}
+
+// ========================================================================= //
+
+/// An entry in an archive's GNU-style (`/`) or BSD-style (`__.SYMDEF`)
+/// symbol lookup table, mapping a symbol name to the member that defines
+/// it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SymbolTableEntry {
+    name: Vec<u8>,
+    offset: u64,
+}
+
+impl SymbolTableEntry {
+    /// Returns the name of this symbol.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the byte offset (from the start of the archive, immediately
+    /// after the global header) of the member that defines this symbol.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// The symbol lookup table of an archive, as produced by `ranlib`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Symbols {
+    entries: Vec<SymbolTableEntry>,
+}
+
+impl Symbols {
+    /// Returns an iterator over the entries of this symbol table.
+    pub fn iter(&self) -> std::slice::Iter<'_, SymbolTableEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the number of symbols in this table.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether this symbol table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Symbols {
+    type Item = &'a SymbolTableEntry;
+    type IntoIter = std::slice::Iter<'a, SymbolTableEntry>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+fn parse_gnu_symbol_table(data: &[u8]) -> io::Result<Symbols> {
+    if data.len() < 4 {
+        return Err(invalid_data("GNU symbol table is too short"));
+    }
+    let count = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let offsets_end = 4 + count
+        .checked_mul(4)
+        .ok_or_else(|| invalid_data("GNU symbol table count overflow"))?;
+    if offsets_end > data.len() {
+        return Err(invalid_data("GNU symbol table is truncated"));
+    }
+    let mut offsets = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = 4 + i * 4;
+        offsets.push(u32::from_be_bytes([
+            data[start],
+            data[start + 1],
+            data[start + 2],
+            data[start + 3],
+        ]) as u64);
+    }
+    let names = &data[offsets_end..];
+    let mut entries = Vec::with_capacity(count);
+    let mut start = 0;
+    for offset in offsets {
+        let end = names[start..]
+            .iter()
+            .position(|&b| b == 0)
+            .map(|pos| start + pos)
+            .ok_or_else(|| invalid_data("GNU symbol table name is not NUL-terminated"))?;
+        entries.push(SymbolTableEntry { name: names[start..end].to_vec(), offset });
+        start = end + 1;
+    }
+    Ok(Symbols { entries })
+}
+
+fn parse_bsd_symbol_table(data: &[u8]) -> io::Result<Symbols> {
+    if data.len() < 4 {
+        return Err(invalid_data("BSD symbol table is too short"));
+    }
+    let ranlib_len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if 4 + ranlib_len + 4 > data.len() || !ranlib_len.is_multiple_of(8) {
+        return Err(invalid_data("BSD symbol table is truncated"));
+    }
+    // The ranlib pairs are followed by a `u32` giving the size of the name
+    // data, then the NUL-terminated names themselves; `ran_strx` indexes
+    // into the names starting just after that size field, not from the
+    // start of `data`.
+    let strings = &data[4 + ranlib_len + 4..];
+    let mut entries = Vec::with_capacity(ranlib_len / 8);
+    for chunk in data[4..4 + ranlib_len].chunks_exact(8) {
+        let str_offset =
+            u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        let member_offset = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        if str_offset > strings.len() {
+            return Err(invalid_data("BSD symbol table string offset out of range"));
+        }
+        let end = strings[str_offset..]
+            .iter()
+            .position(|&b| b == 0)
+            .map(|pos| str_offset + pos)
+            .unwrap_or(strings.len());
+        entries.push(SymbolTableEntry {
+            name: strings[str_offset..end].to_vec(),
+            offset: member_offset,
+        });
+    }
+    Ok(Symbols { entries })
+}
+
+// ========================================================================= //
+
+/// Converts an archive entry's identifier into a path relative to some
+/// extraction directory, rejecting any identifier that could escape it
+/// (an absolute path, or a path with a `..` component).
+fn sanitized_relative_path(identifier: &[u8]) -> io::Result<PathBuf> {
+    #[cfg(unix)]
+    let name = {
+        use std::os::unix::ffi::OsStrExt;
+        std::ffi::OsStr::from_bytes(identifier)
+    };
+    #[cfg(not(unix))]
+    let name = str::from_utf8(identifier)
+        .map_err(|_| invalid_data("Identifier is not valid UTF-8"))?;
+
+    let path = Path::new(name);
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            _ => {
+                return Err(invalid_data(
+                    "Identifier is an unsafe path (absolute, or containing a `..` component)",
+                ));
+            }
+        }
+    }
+    if sanitized.as_os_str().is_empty() {
+        return Err(invalid_data("Identifier is empty"));
+    }
+    Ok(sanitized)
+}
+
+#[cfg(unix)]
+fn restore_metadata(file: &fs::File, header: &Header) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    file.set_permissions(fs::Permissions::from_mode(header.mode()))?;
+
+    // `libc::timeval`'s field widths vary by platform (e.g. 32-bit on
+    // 32-bit Unix targets), so its declaration is used here instead of a
+    // hand-rolled `struct timeval` that would only match the 64-bit ABI.
+    let time = libc::timeval { tv_sec: header.mtime() as libc::time_t, tv_usec: 0 };
+    let times = [time, time];
+    if unsafe { libc::futimes(file.as_raw_fd(), times.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_metadata(_file: &fs::File, _header: &Header) -> io::Result<()> {
+    Ok(())
+}
+
+// ========================================================================= //
+
+/// A structure for reading an archive, decoding it as it goes.
+///
+/// This structure reads members sequentially, never buffering more than one
+/// member's data into memory at a time.
+pub struct Archive<R> {
+    reader: R,
+    scanned_header: bool,
+    variant: Variant,
+    thin: bool,
+    name_table: Vec<u8>,
+    symbols: Symbols,
+    current_entry_remaining: u64,
+    current_entry_padding: u64,
+}
+
+impl<R: Read> Archive<R> {
+    /// Creates a new archive reader from the underlying reader object.
+    pub fn new(reader: R) -> Archive<R> {
+        Archive {
+            reader,
+            scanned_header: false,
+            variant: Variant::Common,
+            thin: false,
+            name_table: Vec::new(),
+            symbols: Symbols::default(),
+            current_entry_remaining: 0,
+            current_entry_padding: 0,
+        }
+    }
+
+    /// Returns the format variant that this archive appears to be encoded
+    /// in. This starts out as a guess (`Common`), and may change as more of
+    /// the archive is read.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Returns whether this is a GNU "thin" archive (identified by the
+    /// `!<thin>\n` global magic), whose members store only headers, with
+    /// each member's data living in an external file named by its
+    /// identifier rather than being embedded in the archive. This is only
+    /// known for certain once the global header has been read, i.e. after
+    /// the first call to `next_entry`.
+    pub fn is_thin(&self) -> bool {
+        self.thin
+    }
+
+    /// Returns the symbol lookup table read from the archive so far (see
+    /// `Symbols`). Since the symbol table, if
+    /// any, is always the first member in the archive, this will be
+    /// complete after the first call to `next_entry`.
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Unwraps this archive reader, returning the underlying reader object.
+    pub fn into_inner(self) -> io::Result<R> {
+        Ok(self.reader)
+    }
+
+    /// Extracts every member of this archive into `dst`, creating it if it
+    /// does not already exist, using each member's `identifier` as its
+    /// filename relative to `dst`. See `Entry::unpack` for details on what
+    /// metadata is restored.
+    ///
+    /// Identifiers are untrusted input, so any that would escape `dst` (an
+    /// absolute path, or one with a `..` component) cause this to return an
+    /// error rather than writing outside of it.
+    pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let dst = dst.as_ref();
+        fs::create_dir_all(dst)?;
+        while let Some(entry) = self.next_entry() {
+            let mut entry = entry?;
+            let path = sanitized_relative_path(entry.header().identifier())?;
+            entry.unpack(dst.join(path))?;
+        }
+        Ok(())
+    }
+
+    fn skip_to_next_header(&mut self) -> io::Result<()> {
+        let mut remaining = self.current_entry_remaining + self.current_entry_padding;
+        let mut buffer = [0u8; 4096];
+        while remaining > 0 {
+            let want = std::cmp::min(remaining, buffer.len() as u64) as usize;
+            let read = self.reader.read(&mut buffer[..want])?;
+            if read == 0 {
+                return Err(invalid_data("Archive is truncated"));
+            }
+            remaining -= read as u64;
+        }
+        self.current_entry_remaining = 0;
+        self.current_entry_padding = 0;
+        Ok(())
+    }
+
+    fn read_exact_or_none(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            let n = self.reader.read(&mut buf[read..])?;
+            if n == 0 {
+                if read == 0 {
+                    return Ok(false);
+                }
+                return Err(invalid_data("Archive is truncated"));
+            }
+            read += n;
+        }
+        Ok(true)
+    }
+
+    /// Reads the next entry from the archive, or returns `None` if there
+    /// are no more entries to be read.
+    pub fn next_entry(&mut self) -> Option<io::Result<Entry<'_, R>>> {
+        loop {
+            if !self.scanned_header {
+                let mut magic = [0u8; GLOBAL_HEADER_LEN];
+                match self.reader.read_exact(&mut magic) {
+                    Ok(()) => {}
+                    Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+                    Err(err) => return Some(Err(err)),
+                }
+                if &magic == GLOBAL_HEADER_THIN {
+                    self.thin = true;
+                    self.variant = Variant::GNU;
+                } else if &magic != GLOBAL_HEADER {
+                    return Some(Err(invalid_data("Invalid global header")));
+                }
+                self.scanned_header = true;
+            } else if let Err(err) = self.skip_to_next_header() {
+                return Some(Err(err));
+            }
+
+            let mut raw_header = [0u8; ENTRY_HEADER_LEN];
+            match self.read_exact_or_none(&mut raw_header) {
+                Ok(false) => return None,
+                Ok(true) => {}
+                Err(err) => return Some(Err(err)),
+            }
+            let RawEntryHeader { mut identifier, mtime, uid, gid, mode, mut size } =
+                match parse_entry_header(&raw_header) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Some(Err(err)),
+                };
+
+            if identifier.as_slice() == GNU_NAME_TABLE_ID.as_bytes() {
+                let mut table = vec![0u8; size as usize];
+                if let Err(err) = self.read_exact_or_none(&mut table) {
+                    return Some(Err(err));
+                }
+                if !size.is_multiple_of(2) {
+                    let mut pad = [0u8; 1];
+                    if let Err(err) = self.reader.read_exact(&mut pad) {
+                        return Some(Err(err));
+                    }
+                }
+                self.name_table = table;
+                self.variant = Variant::GNU;
+                continue;
+            }
+
+            if identifier.as_slice() == GNU_SYMBOL_LOOKUP_TABLE_ID.as_bytes() {
+                let mut table = vec![0u8; size as usize];
+                if let Err(err) = self.read_exact_or_none(&mut table) {
+                    return Some(Err(err));
+                }
+                if !size.is_multiple_of(2) {
+                    let mut pad = [0u8; 1];
+                    if let Err(err) = self.reader.read_exact(&mut pad) {
+                        return Some(Err(err));
+                    }
+                }
+                self.symbols = match parse_gnu_symbol_table(&table) {
+                    Ok(symbols) => symbols,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.variant = Variant::GNU;
+                continue;
+            }
+
+            if identifier.as_slice() == BSD_SYMBOL_LOOKUP_TABLE_ID.as_bytes()
+                || identifier.as_slice() == BSD_SORTED_SYMBOL_LOOKUP_TABLE_ID.as_bytes()
+            {
+                let mut table = vec![0u8; size as usize];
+                if let Err(err) = self.read_exact_or_none(&mut table) {
+                    return Some(Err(err));
+                }
+                if !size.is_multiple_of(2) {
+                    let mut pad = [0u8; 1];
+                    if let Err(err) = self.reader.read_exact(&mut pad) {
+                        return Some(Err(err));
+                    }
+                }
+                self.symbols = match parse_bsd_symbol_table(&table) {
+                    Ok(symbols) => symbols,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.variant = Variant::BSD;
+                continue;
+            }
+
+            if identifier.starts_with(b"#1/") {
+                let name_len = match str::from_utf8(&identifier[3..])
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                {
+                    Some(len) => len,
+                    None => return Some(Err(invalid_data("Invalid BSD extended name length"))),
+                };
+                if name_len as u64 > size {
+                    return Some(Err(invalid_data("BSD extended name longer than member")));
+                }
+                let mut name = vec![0u8; name_len];
+                if let Err(err) = self.read_exact_or_none(&mut name) {
+                    return Some(Err(err));
+                }
+                if let Some(end) = name.iter().position(|&b| b == 0) {
+                    name.truncate(end);
+                }
+                identifier = name;
+                size -= name_len as u64;
+                self.variant = Variant::BSD;
+            } else if identifier.starts_with(b"/") || identifier.ends_with(b"/") {
+                identifier = match resolve_gnu_identifier(identifier, &self.name_table) {
+                    Ok(identifier) => identifier,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.variant = Variant::GNU;
+            }
+
+            let header = Header { identifier, mtime, uid, gid, mode, size };
+            if self.thin {
+                // Thin-archive members carry only a header; the `size` bytes
+                // of content described by it live in an external file, not
+                // in this stream, so there is nothing here to read or skip.
+                self.current_entry_remaining = 0;
+                self.current_entry_padding = 0;
+            } else {
+                self.current_entry_remaining = size;
+                self.current_entry_padding = size % 2;
+            }
+            return Some(Ok(Entry {
+                header,
+                thin: self.thin,
+                reader: &mut self.reader,
+                remaining: &mut self.current_entry_remaining,
+            }));
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// An entry being read from an archive.
+///
+/// This implements `Read` for the raw contents of the member, and will
+/// never read more than `header().size()` bytes.
+pub struct Entry<'a, R: 'a> {
+    header: Header,
+    thin: bool,
+    reader: &'a mut R,
+    remaining: &'a mut u64,
+}
+
+impl<'a, R: 'a + Read> Entry<'a, R> {
+    /// Returns the header for this archive entry.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns whether this entry came from a GNU "thin" archive, meaning
+    /// its data is not embedded here: `header().size()` gives the true
+    /// content length, but reading from this entry always yields EOF, and
+    /// the actual bytes must be read from an external file named by
+    /// `header().identifier()`.
+    pub fn is_thin(&self) -> bool {
+        self.thin
+    }
+
+    /// Extracts the contents of this entry to the file at `dst`, creating
+    /// or truncating it as needed, and returns the number of bytes
+    /// written. On Unix, also restores this entry's `mode` (as the file's
+    /// permissions) and `mtime` (via `futimes`) onto the new file.
+    ///
+    /// For a thin-archive entry (see `is_thin`), this creates an empty
+    /// file, since no data is present in the archive to extract.
+    pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<u64> {
+        let mut file = fs::File::create(dst.as_ref())?;
+        let written = io::copy(self, &mut file)?;
+        restore_metadata(&file, &self.header)?;
+        Ok(written)
+    }
+}
+
+impl<'a, R: 'a + Read> Read for Entry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if *self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = std::cmp::min(*self.remaining, buf.len() as u64) as usize;
+        let bytes_read = self.reader.read(&mut buf[..max])?;
+        *self.remaining -= bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::Builder;
+
+    #[test]
+    fn sanitized_relative_path_rejects_traversal() {
+        assert!(sanitized_relative_path(b"../evil").is_err());
+        assert!(sanitized_relative_path(b"/etc/passwd").is_err());
+        assert!(sanitized_relative_path(b"safe/name.txt").is_ok());
+    }
+
+    #[test]
+    fn archive_unpack_restores_members() {
+        let mut buf = Vec::new();
+        let mut builder = Builder::new(&mut buf);
+        let mut header = Header::new(b"hello.txt".to_vec(), 5);
+        header.set_mode(0o644);
+        builder.append(&header, &mut &b"hello"[..]).unwrap();
+
+        let dir = std::env::temp_dir()
+            .join(format!("ar_read_test_{}_{}_unpack", std::process::id(), line!()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut archive = Archive::new(&buf[..]);
+        archive.unpack(&dir).unwrap();
+        let contents = fs::read(dir.join("hello.txt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(contents, b"hello");
+    }
+
+    #[test]
+    fn archive_unpack_rejects_path_traversal() {
+        let mut buf = Vec::new();
+        let mut builder = Builder::new(&mut buf);
+        builder.append(&Header::new(b"../evil.txt".to_vec(), 4), &mut &b"evil"[..]).unwrap();
+
+        let dir = std::env::temp_dir()
+            .join(format!("ar_read_test_{}_{}_traversal", std::process::id(), line!()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut archive = Archive::new(&buf[..]);
+        let result = archive.unpack(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+    }
+}
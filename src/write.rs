@@ -0,0 +1,722 @@
+use super::{
+    Header, BSD_SORTED_SYMBOL_LOOKUP_TABLE_ID, BSD_SYMBOL_LOOKUP_TABLE_ID, ENTRY_HEADER_LEN,
+    GLOBAL_HEADER, GLOBAL_HEADER_THIN, GNU_NAME_TABLE_ID, GNU_SYMBOL_LOOKUP_TABLE_ID,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// ========================================================================= //
+
+fn pad_header_field(dst: &mut [u8], value: &[u8]) {
+    let len = std::cmp::min(dst.len(), value.len());
+    dst[..len].copy_from_slice(&value[..len]);
+    for byte in dst[len..].iter_mut() {
+        *byte = b' ';
+    }
+}
+
+/// Builds the raw 60-byte on-disk representation of an entry header. This is
+/// pure (no I/O), so it can be shared between the synchronous writer below
+/// and the `tokio`-based `AsyncBuilder`.
+pub(crate) fn build_entry_header_bytes(
+    identifier: &[u8],
+    header: &Header,
+    size: u64,
+) -> [u8; ENTRY_HEADER_LEN] {
+    let mut raw = [b' '; ENTRY_HEADER_LEN];
+    pad_header_field(&mut raw[0..16], identifier);
+    pad_header_field(&mut raw[16..28], header.mtime().to_string().as_bytes());
+    pad_header_field(&mut raw[28..34], header.uid().to_string().as_bytes());
+    pad_header_field(&mut raw[34..40], header.gid().to_string().as_bytes());
+    pad_header_field(&mut raw[40..48], format!("{:o}", header.mode()).as_bytes());
+    pad_header_field(&mut raw[48..58], size.to_string().as_bytes());
+    raw[58] = b'`';
+    raw[59] = b'\n';
+    raw
+}
+
+fn write_entry_header<W: Write>(
+    writer: &mut W,
+    identifier: &[u8],
+    header: &Header,
+    size: u64,
+) -> io::Result<()> {
+    writer.write_all(&build_entry_header_bytes(identifier, header, size))
+}
+
+fn write_data_and_padding<W: Write, R: Read>(
+    writer: &mut W,
+    data: &mut R,
+    size: u64,
+) -> io::Result<()> {
+    let mut limited = data.take(size);
+    let copied = io::copy(&mut limited, writer)?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Wrote fewer bytes than the header's declared size",
+        ));
+    }
+    if !size.is_multiple_of(2) {
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Returns the number of bytes a member with the given content size takes
+/// up on disk, including its 60-byte header and 2-byte alignment padding.
+fn member_disk_size(content_size: u64) -> u64 {
+    ENTRY_HEADER_LEN as u64 + content_size + (content_size % 2)
+}
+
+/// Computes the on-disk byte offset (from just after the global header) of
+/// each member in `members`, assuming the members are laid out in order
+/// starting at `base_offset`.
+fn compute_member_offsets(members: &[(Vec<u8>, u64)], base_offset: u64) -> BTreeMap<Vec<u8>, u64> {
+    let mut offsets = BTreeMap::new();
+    let mut cursor = base_offset;
+    for (identifier, size) in members {
+        offsets.insert(identifier.clone(), cursor);
+        cursor += member_disk_size(*size);
+    }
+    offsets
+}
+
+fn symbol_not_appended_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Symbol table refers to a member that is not in the member list",
+    )
+}
+
+fn symbol_names_blob(symbol_table: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut blob = Vec::new();
+    for symbol in symbol_table.keys() {
+        blob.extend_from_slice(symbol);
+        blob.push(0);
+    }
+    blob
+}
+
+fn build_gnu_symbol_table(
+    symbol_table: &BTreeMap<Vec<u8>, Vec<u8>>,
+    names_blob: &[u8],
+    offsets: &BTreeMap<Vec<u8>, u64>,
+) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(4 + symbol_table.len() * 4 + names_blob.len());
+    data.extend_from_slice(&(symbol_table.len() as u32).to_be_bytes());
+    for member in symbol_table.values() {
+        let offset = *offsets.get(member).ok_or_else(symbol_not_appended_error)?;
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+    }
+    data.extend_from_slice(names_blob);
+    Ok(data)
+}
+
+fn build_bsd_symbol_table(
+    symbol_table: &BTreeMap<Vec<u8>, Vec<u8>>,
+    names_blob: &[u8],
+    offsets: &BTreeMap<Vec<u8>, u64>,
+) -> io::Result<Vec<u8>> {
+    let ranlib_len = (symbol_table.len() * 8) as u32;
+    let mut data = Vec::with_capacity(8 + symbol_table.len() * 8 + names_blob.len());
+    data.extend_from_slice(&ranlib_len.to_le_bytes());
+    let mut name_cursor = 0u32;
+    for (symbol, member) in symbol_table {
+        let offset = *offsets.get(member).ok_or_else(symbol_not_appended_error)?;
+        data.extend_from_slice(&name_cursor.to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        name_cursor += symbol.len() as u32 + 1;
+    }
+    // The `ran_strx` offsets above index into the name data starting just
+    // after this string-table-size field, not from the start of `data`, so
+    // it must be written before `names_blob` even though nothing else
+    // references its value.
+    data.extend_from_slice(&(names_blob.len() as u32).to_le_bytes());
+    data.extend_from_slice(names_blob);
+    Ok(data)
+}
+
+fn check_against_expected(
+    expected_members: &mut Option<VecDeque<(Vec<u8>, u64)>>,
+    identifier: &[u8],
+    size: u64,
+) -> io::Result<()> {
+    if let Some(expected) = expected_members.as_mut() {
+        match expected.pop_front() {
+            Some((ref exp_id, exp_size)) if exp_id.as_slice() == identifier && exp_size == size => {
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Appended member does not match the member list given when the symbol table \
+                 was built",
+            )),
+        }
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that every member declared when a `new_with_symbol_table` builder
+/// was constructed has actually been appended, returning an error if not.
+///
+/// Without this, finalizing an archive that's short of members it promised
+/// would silently succeed with a ranlib symbol table whose offsets no
+/// longer correspond to where members actually ended up in the file.
+fn check_all_expected_appended(
+    expected_members: &Option<VecDeque<(Vec<u8>, u64)>>,
+) -> io::Result<()> {
+    match expected_members {
+        Some(expected) if !expected.is_empty() => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} member(s) declared when the symbol table was built were never appended",
+                expected.len()
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// ========================================================================= //
+
+/// The deterministic/reproducible mode of an archive being written.
+///
+/// This mirrors the `tar` crate's `HeaderMode`: it controls what happens to
+/// filesystem-derived metadata (`mtime`, `uid`, `gid`, `mode`) when a header
+/// is built from `Header::from_metadata` via `append_file`/`append_path`.
+/// Headers constructed explicitly by the caller and passed to `append` are
+/// never touched, regardless of the mode in effect.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HeaderMode {
+    /// Preserve all filesystem metadata as-is (the default).
+    #[default]
+    Complete,
+    /// Zero out `mtime`, `uid`, and `gid`, and normalize `mode` to a
+    /// canonical value (`0o644`, or `0o755` if any execute bit was set),
+    /// so that archives built from the same inputs are byte-for-byte
+    /// identical regardless of the machine or user that built them.
+    Deterministic,
+}
+
+impl HeaderMode {
+    fn normalize(self, header: &mut Header) {
+        if let HeaderMode::Deterministic = self {
+            header.set_mtime(0);
+            header.set_uid(0);
+            header.set_gid(0);
+            let mode = if header.mode() & 0o111 != 0 { 0o755 } else { 0o644 };
+            header.set_mode(mode);
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// A structure for building common-variant (and BSD-variant) archives that
+/// are written out in one go to a writer.
+///
+/// This structure writes a global archive header up front, then writes each
+/// member in turn as `append`/`append_file`/`append_path` are called,
+/// without ever buffering a whole member's data into memory.
+pub struct Builder<W: Write> {
+    writer: W,
+    started: bool,
+    mode: HeaderMode,
+    expected_members: Option<VecDeque<(Vec<u8>, u64)>>,
+}
+
+impl<W: Write> Builder<W> {
+    /// Creates a new archive builder with the underlying writer object,
+    /// to be written in the standard "common" format.
+    pub fn new(writer: W) -> Builder<W> {
+        Builder { writer, started: false, mode: HeaderMode::Complete, expected_members: None }
+    }
+
+    /// Creates a new archive builder that, before any members are
+    /// appended, writes a BSD-style (`__.SYMDEF`) symbol lookup table as the
+    /// first member, mapping each symbol name to the byte offset of the
+    /// member (from `members`, in append order) that defines it.
+    ///
+    /// Because the table's own size affects the offset of every member
+    /// that follows it, the full list of members (with their identifiers
+    /// and content sizes) must be known up front. Each subsequent call to
+    /// `append`/`append_file`/`append_path` must supply members matching
+    /// `members`, in order, or an error is returned.
+    pub fn new_with_symbol_table(
+        writer: W,
+        members: Vec<(Vec<u8>, u64)>,
+        symbol_table: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> io::Result<Builder<W>> {
+        Builder::new_with_symbol_table_impl(writer, members, symbol_table, false)
+    }
+
+    /// Like `new_with_symbol_table`, but emits a `__.SYMDEF SORTED` table
+    /// instead of a plain `__.SYMDEF` table, indicating to readers that the
+    /// symbols are sorted by name (which they always are, since this crate
+    /// orders them by the `BTreeMap` key).
+    pub fn new_with_sorted_symbol_table(
+        writer: W,
+        members: Vec<(Vec<u8>, u64)>,
+        symbol_table: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> io::Result<Builder<W>> {
+        Builder::new_with_symbol_table_impl(writer, members, symbol_table, true)
+    }
+
+    fn new_with_symbol_table_impl(
+        mut writer: W,
+        members: Vec<(Vec<u8>, u64)>,
+        symbol_table: BTreeMap<Vec<u8>, Vec<u8>>,
+        sorted: bool,
+    ) -> io::Result<Builder<W>> {
+        for (identifier, _) in &members {
+            if identifier.len() > 16 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Identifier is too long for this archive variant",
+                ));
+            }
+        }
+
+        let names_blob = symbol_names_blob(&symbol_table);
+        let content_len = 4 + symbol_table.len() as u64 * 8 + 4 + names_blob.len() as u64;
+        let base_offset = member_disk_size(content_len);
+        let offsets = compute_member_offsets(&members, base_offset);
+        let data = build_bsd_symbol_table(&symbol_table, &names_blob, &offsets)?;
+
+        writer.write_all(GLOBAL_HEADER)?;
+        let table_id =
+            if sorted { BSD_SORTED_SYMBOL_LOOKUP_TABLE_ID } else { BSD_SYMBOL_LOOKUP_TABLE_ID };
+        let table_header = Header::new(table_id.as_bytes().to_vec(), data.len() as u64);
+        write_entry_header(&mut writer, table_id.as_bytes(), &table_header, data.len() as u64)?;
+        write_data_and_padding(&mut writer, &mut &data[..], data.len() as u64)?;
+
+        Ok(Builder {
+            writer,
+            started: true,
+            mode: HeaderMode::Complete,
+            expected_members: Some(members.into_iter().collect()),
+        })
+    }
+
+    /// Sets the mode used for headers derived from filesystem metadata in
+    /// subsequent `append_file`/`append_path` calls. See `HeaderMode` for
+    /// details. Defaults to `HeaderMode::Complete`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.mode = mode;
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.writer.write_all(GLOBAL_HEADER)?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive with the given header and contents.
+    /// The header's identifier must be no more than 16 bytes, and must not
+    /// contain any whitespace.
+    pub fn append<R: Read>(&mut self, header: &Header, data: &mut R) -> io::Result<()> {
+        self.start()?;
+        let identifier = header.identifier();
+        if identifier.len() > 16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Identifier in header is too long for this archive variant",
+            ));
+        }
+        if identifier.iter().any(u8::is_ascii_whitespace) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Identifier in header contains whitespace",
+            ));
+        }
+        check_against_expected(&mut self.expected_members, identifier, header.size())?;
+        write_entry_header(&mut self.writer, identifier, header, header.size())?;
+        write_data_and_padding(&mut self.writer, data, header.size())
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the given
+    /// name as the identifier for the archive entry.
+    pub fn append_file(&mut self, name: &[u8], file: &mut File) -> io::Result<()> {
+        let metadata = file.metadata()?;
+        let mut header = Header::from_metadata(name.to_vec(), &metadata);
+        self.mode.normalize(&mut header);
+        self.append(&header, file)
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the
+    /// file's base name as the identifier for the archive entry.
+    pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Given path doesn't have a file name")
+            })?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes();
+        let mut file = File::open(path)?;
+        self.append_file(&name, &mut file)
+    }
+
+    /// Unwraps this archive builder, returning the underlying writer object.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.start()?;
+        check_all_expected_appended(&self.expected_members)?;
+        Ok(self.writer)
+    }
+}
+
+// ========================================================================= //
+
+fn gnu_identifier_for_name(name: &[u8], name_positions: &BTreeMap<Vec<u8>, usize>) -> Vec<u8> {
+    if name.len() <= 15 {
+        let mut identifier = name.to_vec();
+        identifier.push(b'/');
+        identifier
+    } else {
+        format!("/{}", name_positions[name]).into_bytes()
+    }
+}
+
+/// A structure for building GNU-variant archives that are written out in
+/// one go to a writer.
+///
+/// Because the GNU long-name table must be written before any of the
+/// members that reference it, the full set of entry identifiers must be
+/// known up front, when the builder is constructed.
+pub struct GnuBuilder<W: Write> {
+    writer: W,
+    started: bool,
+    mode: HeaderMode,
+    thin: bool,
+    name_positions: BTreeMap<Vec<u8>, usize>,
+    expected_members: Option<VecDeque<(Vec<u8>, u64)>>,
+}
+
+impl<W: Write> GnuBuilder<W> {
+    /// Creates a new archive builder with the underlying writer object,
+    /// to be written in the GNU format. The `identifiers` give the
+    /// identifier of every member that will be appended to this archive,
+    /// in order, so that the long name table can be built up front.
+    pub fn new(writer: W, identifiers: Vec<Vec<u8>>) -> io::Result<GnuBuilder<W>> {
+        GnuBuilder::new_impl(writer, identifiers, false)
+    }
+
+    /// Creates a new archive builder like `new`, but writes the `!<thin>\n`
+    /// global magic and expects members to be appended with
+    /// `append_thin_path` rather than `append`/`append_file`/`append_path`:
+    /// only each member's header is written, with its data expected to live
+    /// in an external file (named by its identifier) alongside the archive,
+    /// rather than being embedded.
+    pub fn new_thin(writer: W, identifiers: Vec<Vec<u8>>) -> io::Result<GnuBuilder<W>> {
+        GnuBuilder::new_impl(writer, identifiers, true)
+    }
+
+    fn new_impl(writer: W, identifiers: Vec<Vec<u8>>, thin: bool) -> io::Result<GnuBuilder<W>> {
+        let mut name_table = Vec::new();
+        let mut name_positions = BTreeMap::new();
+        for identifier in identifiers {
+            if identifier.len() > 15 {
+                let position = name_table.len();
+                name_table.extend_from_slice(&identifier);
+                name_table.extend_from_slice(b"/\n");
+                name_positions.insert(identifier, position);
+            }
+        }
+        let mut builder = GnuBuilder {
+            writer,
+            started: false,
+            mode: HeaderMode::Complete,
+            thin,
+            name_positions,
+            expected_members: None,
+        };
+        if !name_table.is_empty() {
+            builder.start()?;
+            let size = name_table.len() as u64;
+            let dummy = Header::new(GNU_NAME_TABLE_ID.as_bytes().to_vec(), size);
+            write_entry_header(&mut builder.writer, GNU_NAME_TABLE_ID.as_bytes(), &dummy, size)?;
+            write_data_and_padding(&mut builder.writer, &mut &name_table[..], size)?;
+        }
+        Ok(builder)
+    }
+
+    /// Creates a new archive builder that, before any members are
+    /// appended, writes a GNU-style (`/`) symbol lookup table as the first
+    /// member, followed by the GNU long-name table (if needed), mapping each
+    /// symbol name to the byte offset of the member (from `members`, in
+    /// append order) that defines it.
+    ///
+    /// As with `new`, the full set of members must be known up front, since
+    /// both tables affect the offsets of the members that follow them. Each
+    /// subsequent call to `append`/`append_file`/`append_path` must supply
+    /// members matching `members`, in order, or an error is returned.
+    pub fn new_with_symbol_table(
+        mut writer: W,
+        members: Vec<(Vec<u8>, u64)>,
+        symbol_table: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> io::Result<GnuBuilder<W>> {
+        let mut name_table = Vec::new();
+        let mut name_positions = BTreeMap::new();
+        for (identifier, _) in &members {
+            if identifier.len() > 15 {
+                let position = name_table.len();
+                name_table.extend_from_slice(identifier);
+                name_table.extend_from_slice(b"/\n");
+                name_positions.insert(identifier.clone(), position);
+            }
+        }
+
+        let names_blob = symbol_names_blob(&symbol_table);
+        let content_len = 4 + symbol_table.len() as u64 * 4 + names_blob.len() as u64;
+        let symbol_header_size = member_disk_size(content_len);
+        let name_table_header_size =
+            if name_table.is_empty() { 0 } else { member_disk_size(name_table.len() as u64) };
+        let base_offset = symbol_header_size + name_table_header_size;
+        let offsets = compute_member_offsets(&members, base_offset);
+        let data = build_gnu_symbol_table(&symbol_table, &names_blob, &offsets)?;
+
+        writer.write_all(GLOBAL_HEADER)?;
+        let table_header = Header::new(GNU_SYMBOL_LOOKUP_TABLE_ID.as_bytes().to_vec(), data.len() as u64);
+        write_entry_header(
+            &mut writer,
+            GNU_SYMBOL_LOOKUP_TABLE_ID.as_bytes(),
+            &table_header,
+            data.len() as u64,
+        )?;
+        write_data_and_padding(&mut writer, &mut &data[..], data.len() as u64)?;
+
+        if !name_table.is_empty() {
+            let size = name_table.len() as u64;
+            let dummy = Header::new(GNU_NAME_TABLE_ID.as_bytes().to_vec(), size);
+            write_entry_header(&mut writer, GNU_NAME_TABLE_ID.as_bytes(), &dummy, size)?;
+            write_data_and_padding(&mut writer, &mut &name_table[..], size)?;
+        }
+
+        Ok(GnuBuilder {
+            writer,
+            started: true,
+            mode: HeaderMode::Complete,
+            thin: false,
+            name_positions,
+            expected_members: Some(members.into_iter().collect()),
+        })
+    }
+
+    /// Sets the mode used for headers derived from filesystem metadata in
+    /// subsequent `append_file`/`append_path` calls. See `HeaderMode` for
+    /// details. Defaults to `HeaderMode::Complete`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.mode = mode;
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            let magic = if self.thin { GLOBAL_HEADER_THIN } else { GLOBAL_HEADER };
+            self.writer.write_all(magic)?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive with the given header and contents.
+    /// The header's identifier must have been included in the list of
+    /// identifiers passed to `GnuBuilder::new`.
+    pub fn append<R: Read>(&mut self, header: &Header, data: &mut R) -> io::Result<()> {
+        self.start()?;
+        check_against_expected(&mut self.expected_members, header.identifier(), header.size())?;
+        let identifier = gnu_identifier_for_name(header.identifier(), &self.name_positions);
+        write_entry_header(&mut self.writer, &identifier, header, header.size())?;
+        write_data_and_padding(&mut self.writer, data, header.size())
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the given
+    /// name as the identifier for the archive entry.
+    pub fn append_file(&mut self, name: &[u8], file: &mut File) -> io::Result<()> {
+        let metadata = file.metadata()?;
+        let mut header = Header::from_metadata(name.to_vec(), &metadata);
+        self.mode.normalize(&mut header);
+        self.append(&header, file)
+    }
+
+    /// Adds a file on the local filesystem to this archive, using the
+    /// file's base name as the identifier for the archive entry.
+    pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Given path doesn't have a file name")
+            })?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes();
+        let mut file = File::open(path)?;
+        self.append_file(&name, &mut file)
+    }
+
+    /// Appends a *reference* to a file on the local filesystem as a thin
+    /// archive member, using the given path itself (not just its base
+    /// name) as the identifier: only a header is written, describing the
+    /// file's real size and metadata, and the file's contents are never
+    /// opened, read, or copied into the archive. Since a thin member's
+    /// identifier is how a later reader locates the real file, callers
+    /// should pass a path relative to the archive's own location (or an
+    /// absolute one) rather than a bare file name, unless the referenced
+    /// file genuinely lives alongside the archive. This builder must have
+    /// been created with `new_thin`.
+    pub fn append_thin_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if !self.thin {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "append_thin_path can only be used on a thin archive (see GnuBuilder::new_thin)",
+            ));
+        }
+        self.start()?;
+        let path = path.as_ref();
+        let name = path.as_os_str().to_string_lossy().into_owned().into_bytes();
+        let metadata = std::fs::metadata(path)?;
+        let mut header = Header::from_metadata(name, &metadata);
+        self.mode.normalize(&mut header);
+        check_against_expected(&mut self.expected_members, header.identifier(), header.size())?;
+        let identifier = gnu_identifier_for_name(header.identifier(), &self.name_positions);
+        write_entry_header(&mut self.writer, &identifier, &header, header.size())
+    }
+
+    /// Unwraps this archive builder, returning the underlying writer object.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.start()?;
+        check_all_expected_appended(&self.expected_members)?;
+        Ok(self.writer)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::Archive;
+
+    fn temp_dir(label: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("ar_write_test_{}_{}_{}", std::process::id(), label, line!()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn header_mode_deterministic_zeroes_filesystem_metadata() {
+        let dir = temp_dir("header_mode");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, b"hello").unwrap();
+
+        let mut buf = Vec::new();
+        let mut builder = Builder::new(&mut buf);
+        builder.mode(HeaderMode::Deterministic);
+        builder.append_path(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut archive = Archive::new(&buf[..]);
+        let entry = archive.next_entry().unwrap().unwrap();
+        assert_eq!(entry.header().mtime(), 0);
+        assert_eq!(entry.header().uid(), 0);
+        assert_eq!(entry.header().gid(), 0);
+        assert!(entry.header().mode() == 0o644 || entry.header().mode() == 0o755);
+    }
+
+    fn assert_symbol_offsets_match<R: Read>(mut archive: Archive<R>, buf: &[u8]) {
+        while let Some(entry) = archive.next_entry() {
+            entry.unwrap();
+        }
+        let symbols: Vec<_> = archive.symbols().iter().cloned().collect();
+        assert_eq!(symbols.len(), 2);
+        for symbol in &symbols {
+            let header_start = crate::GLOBAL_HEADER_LEN + symbol.offset() as usize;
+            let field = &buf[header_start..header_start + 16];
+            let expected: &[u8] = match symbol.name() {
+                b"foo" => b"a.o",
+                b"bar" => b"b.o",
+                other => panic!("unexpected symbol {:?}", other),
+            };
+            assert!(
+                field.starts_with(expected),
+                "offset for {:?} didn't point at its member",
+                symbol.name()
+            );
+        }
+    }
+
+    #[test]
+    fn gnu_symbol_table_offsets_point_at_right_members() {
+        let members = vec![(b"a.o".to_vec(), 3), (b"b.o".to_vec(), 3)];
+        let mut symbol_table = BTreeMap::new();
+        symbol_table.insert(b"foo".to_vec(), b"a.o".to_vec());
+        symbol_table.insert(b"bar".to_vec(), b"b.o".to_vec());
+
+        let mut buf = Vec::new();
+        let mut builder =
+            GnuBuilder::new_with_symbol_table(&mut buf, members, symbol_table).unwrap();
+        builder.append(&Header::new(b"a.o".to_vec(), 3), &mut &b"AAA"[..]).unwrap();
+        builder.append(&Header::new(b"b.o".to_vec(), 3), &mut &b"BBB"[..]).unwrap();
+        builder.into_inner().unwrap();
+
+        assert_symbol_offsets_match(Archive::new(&buf[..]), &buf);
+    }
+
+    #[test]
+    fn bsd_symbol_table_offsets_point_at_right_members() {
+        let members = vec![(b"a.o".to_vec(), 3), (b"b.o".to_vec(), 3)];
+        let mut symbol_table = BTreeMap::new();
+        symbol_table.insert(b"foo".to_vec(), b"a.o".to_vec());
+        symbol_table.insert(b"bar".to_vec(), b"b.o".to_vec());
+
+        let mut buf = Vec::new();
+        let mut builder =
+            Builder::new_with_symbol_table(&mut buf, members, symbol_table).unwrap();
+        builder.append(&Header::new(b"a.o".to_vec(), 3), &mut &b"AAA"[..]).unwrap();
+        builder.append(&Header::new(b"b.o".to_vec(), 3), &mut &b"BBB"[..]).unwrap();
+        builder.into_inner().unwrap();
+
+        assert_symbol_offsets_match(Archive::new(&buf[..]), &buf);
+    }
+
+    #[test]
+    fn into_inner_rejects_missing_declared_members() {
+        let members = vec![(b"a.o".to_vec(), 1), (b"b.o".to_vec(), 1)];
+        let mut buf = Vec::new();
+        let mut builder =
+            Builder::new_with_symbol_table(&mut buf, members, BTreeMap::new()).unwrap();
+        builder.append(&Header::new(b"a.o".to_vec(), 1), &mut &b"x"[..]).unwrap();
+        assert!(builder.into_inner().is_err());
+    }
+
+    #[test]
+    fn thin_archive_round_trip_preserves_directory_components() {
+        let dir = temp_dir("thin");
+        std::fs::create_dir_all(dir.join("subdir")).unwrap();
+        let member_path = dir.join("subdir").join("foo.o");
+        std::fs::write(&member_path, b"hello").unwrap();
+        let identifier = member_path.as_os_str().to_string_lossy().into_owned().into_bytes();
+
+        let mut buf = Vec::new();
+        let mut builder = GnuBuilder::new_thin(&mut buf, vec![identifier.clone()]).unwrap();
+        builder.append_thin_path(&member_path).unwrap();
+        builder.into_inner().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut archive = Archive::new(&buf[..]);
+        let entry = archive.next_entry().unwrap().unwrap();
+        assert_eq!(entry.header().identifier(), identifier.as_slice());
+        assert!(entry.is_thin());
+        drop(entry);
+        assert!(archive.is_thin());
+    }
+}